@@ -6,7 +6,7 @@ use open_metrics_client::metrics::counter::Counter;
 use open_metrics_client::metrics::family::Family;
 use open_metrics_client::metrics::histogram::{exponential_series, Histogram};
 use open_metrics_client::registry::Registry;
-use std::io::Write;
+use std::fmt::Write;
 use std::sync::atomic::AtomicU64;
 
 pub fn text(c: &mut Criterion) {
@@ -35,14 +35,13 @@ pub fn text(c: &mut Criterion) {
         };
 
         impl Encode for Status {
-            fn encode(&self, writer: &mut dyn Write) -> Result<(), std::io::Error> {
+            fn encode(&self, writer: &mut dyn Write) -> std::fmt::Result {
                 let status = match self {
-                    Status::Two => b"200",
-                    Status::Four => b"400",
-                    Status::Five => b"500",
+                    Status::Two => "200",
+                    Status::Four => "400",
+                    Status::Five => "500",
                 };
-                writer.write_all(status)?;
-                Ok(())
+                writer.write_str(status)
             }
         }
 
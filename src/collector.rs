@@ -0,0 +1,72 @@
+//! Support for metrics computed lazily at collection (i.e. scrape) time.
+//!
+//! Unlike a [`Counter`](crate::metrics::counter::Counter) or
+//! [`Gauge`](crate::metrics::gauge::Gauge), a [`Collector`] is not a
+//! long-lived object registered once and updated in place. Instead it is
+//! asked to produce its descriptors and metrics on demand, every time the
+//! registry is encoded. This is useful for bridging values that live
+//! elsewhere - process or OS stats, or numbers pulled from another system -
+//! without having to mirror them into a registered metric on every update.
+
+use crate::encoding::text::EncodeMetric;
+use crate::registry::Descriptor;
+
+/// A metrics collector, queried at collection time rather than updated in
+/// place.
+///
+/// Register one with [`Registry::register_collector`](crate::registry::Registry::register_collector).
+///
+/// Requires `Send`, mirroring [`SendEncodeMetric`](crate::encoding::text::SendEncodeMetric):
+/// `Registry::collectors` isn't parameterized the way `Registry<M>`'s
+/// `metrics` field is, so there's no per-use-site way to opt out the way `M`
+/// lets a caller pick `Box<dyn SendEncodeMetric>`. Without this bound, a
+/// single registered collector would make the whole `Registry` non-`Send`,
+/// breaking the common "put the registry behind an `Arc` for the HTTP
+/// metrics endpoint" usage this crate is built for.
+pub trait Collector: Send {
+    /// Returns an iterator of the descriptors and metrics this collector
+    /// currently has to offer.
+    ///
+    /// Called once per encode, i.e. once per scrape, so implementations are
+    /// free to compute their values lazily here rather than keeping them up
+    /// to date continuously.
+    fn collect<'a>(&'a self) -> Box<dyn Iterator<Item = (Descriptor, Box<dyn EncodeMetric + 'a>)> + 'a>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoding::text::encode;
+    use crate::metrics::counter::Counter;
+    use crate::registry::Registry;
+    use std::sync::atomic::AtomicU64;
+
+    struct MyCollector {
+        counter: Counter<AtomicU64>,
+    }
+
+    impl Collector for MyCollector {
+        fn collect<'a>(
+            &'a self,
+        ) -> Box<dyn Iterator<Item = (Descriptor, Box<dyn EncodeMetric + 'a>)> + 'a> {
+            let descriptor = Descriptor::new("my_collected_counter", "My collected counter", None);
+            let metric = Box::new(self.counter.clone()) as Box<dyn EncodeMetric>;
+            Box::new(std::iter::once((descriptor, metric)))
+        }
+    }
+
+    #[test]
+    fn collected_metrics_are_encoded() {
+        let counter = Counter::<AtomicU64>::new();
+        counter.inc();
+
+        let mut registry = Registry::<Box<dyn EncodeMetric>>::default();
+        registry.register_collector(Box::new(MyCollector { counter }));
+
+        let mut encoded = Vec::new();
+        encode(&mut encoded, &registry).unwrap();
+        let encoded = String::from_utf8(encoded).unwrap();
+
+        assert!(encoded.contains("my_collected_counter_total 1"));
+    }
+}
@@ -0,0 +1,176 @@
+//! Encoding of metrics and their descriptions into an OpenMetrics exposition
+//! format.
+//!
+//! Two formats are implemented:
+//!
+//!   * [`text`], the OpenMetrics text format, always available.
+//!   * [`protobuf`], the OpenMetrics protobuf format, available behind the
+//!     `protobuf` feature.
+//!
+//! [`EncodeMetric`](text::EncodeMetric) implementations are written
+//! once against the [`MetricEncoder`] abstraction below rather than once per
+//! format, so `Counter`, `Gauge`, `Histogram` and `Family` stay agnostic of
+//! which exposition format is requested.
+
+pub mod text;
+
+#[cfg(feature = "protobuf")]
+pub mod protobuf;
+
+use crate::metrics::exemplar::Exemplar;
+
+/// Encodes a single metric, abstracting over the concrete exposition format.
+///
+/// Implemented as an enum rather than a trait so it stays usable behind a
+/// `Box<dyn EncodeMetric>` (see the object-safety note on
+/// [`text::EncodeMetric`]), the same reason [`text::Encoder`] itself does not
+/// take a type parameter for its writer.
+pub enum MetricEncoder<'a, 'b> {
+    Text(text::Encoder<'a, 'b>),
+    #[cfg(feature = "protobuf")]
+    Protobuf(protobuf::MetricEncoder<'a>),
+}
+
+impl<'a, 'b> MetricEncoder<'a, 'b> {
+    pub fn encode_suffix(
+        &mut self,
+        suffix: &'static str,
+    ) -> Result<BucketEncoder<'_>, std::fmt::Error> {
+        Ok(match self {
+            MetricEncoder::Text(e) => BucketEncoder::Text(e.encode_suffix(suffix)?),
+            #[cfg(feature = "protobuf")]
+            MetricEncoder::Protobuf(e) => BucketEncoder::Protobuf(e.encode_suffix(suffix)?),
+        })
+    }
+
+    pub fn no_suffix(&mut self) -> Result<BucketEncoder<'_>, std::fmt::Error> {
+        Ok(match self {
+            MetricEncoder::Text(e) => BucketEncoder::Text(e.no_suffix()?),
+            #[cfg(feature = "protobuf")]
+            MetricEncoder::Protobuf(e) => BucketEncoder::Protobuf(e.no_suffix()?),
+        })
+    }
+
+    pub fn with_label_set<'c, 'd>(
+        &'c mut self,
+        label_set: &'d dyn text::Encode,
+    ) -> MetricEncoder<'c, 'd> {
+        match self {
+            MetricEncoder::Text(e) => MetricEncoder::Text(e.with_label_set(label_set)),
+            #[cfg(feature = "protobuf")]
+            MetricEncoder::Protobuf(e) => MetricEncoder::Protobuf(e.with_label_set(label_set)),
+        }
+    }
+
+    /// Encodes a counter's `_total` sample, optionally under `label_set`. See
+    /// [`text::Encoder::encode_counter`].
+    pub fn encode_counter<V: EncodeValue>(
+        &mut self,
+        label_set: Option<&dyn text::Encode>,
+        v: V,
+    ) -> std::fmt::Result {
+        match self {
+            MetricEncoder::Text(e) => e.encode_counter(label_set, v),
+            #[cfg(feature = "protobuf")]
+            MetricEncoder::Protobuf(e) => e.encode_counter(label_set, v.as_f64()),
+        }
+    }
+
+    /// Encodes a gauge's sample, optionally under `label_set`. See
+    /// [`text::Encoder::encode_gauge`].
+    pub fn encode_gauge<V: EncodeValue>(
+        &mut self,
+        label_set: Option<&dyn text::Encode>,
+        v: V,
+    ) -> std::fmt::Result {
+        match self {
+            MetricEncoder::Text(e) => e.encode_gauge(label_set, v),
+            #[cfg(feature = "protobuf")]
+            MetricEncoder::Protobuf(e) => e.encode_gauge(label_set, v.as_f64()),
+        }
+    }
+}
+
+#[must_use]
+pub enum BucketEncoder<'a> {
+    Text(text::BucketEncoder<'a>),
+    #[cfg(feature = "protobuf")]
+    Protobuf(protobuf::BucketEncoder<'a>),
+}
+
+impl<'a> BucketEncoder<'a> {
+    pub fn encode_bucket(&mut self, upper_bound: f64) -> Result<ValueEncoder<'_>, std::fmt::Error> {
+        Ok(match self {
+            BucketEncoder::Text(e) => ValueEncoder::Text(e.encode_bucket(upper_bound)?),
+            #[cfg(feature = "protobuf")]
+            BucketEncoder::Protobuf(e) => ValueEncoder::Protobuf(e.encode_bucket(upper_bound)?),
+        })
+    }
+
+    pub fn no_bucket(&mut self) -> Result<ValueEncoder<'_>, std::fmt::Error> {
+        Ok(match self {
+            BucketEncoder::Text(e) => ValueEncoder::Text(e.no_bucket()?),
+            #[cfg(feature = "protobuf")]
+            BucketEncoder::Protobuf(e) => ValueEncoder::Protobuf(e.no_bucket()?),
+        })
+    }
+}
+
+#[must_use]
+pub enum ValueEncoder<'a> {
+    Text(text::ValueEncoder<'a>),
+    #[cfg(feature = "protobuf")]
+    Protobuf(protobuf::ValueEncoder<'a>),
+}
+
+impl<'a> ValueEncoder<'a> {
+    pub fn encode_value<V: EncodeValue>(&mut self, v: V) -> std::fmt::Result {
+        match self {
+            ValueEncoder::Text(e) => e.encode_value(v),
+            #[cfg(feature = "protobuf")]
+            ValueEncoder::Protobuf(e) => e.encode_value(v.as_f64()),
+        }
+    }
+
+    /// Like [`Self::encode_value`], but also encodes an [`Exemplar`] if one
+    /// is provided. Only meaningful on a counter's `_total` sample or a
+    /// histogram's `_bucket` samples; callers elsewhere simply pass `None`.
+    pub fn encode_value_and_exemplar<V: EncodeValue, ES: EncodeValue, S: text::Encode>(
+        &mut self,
+        v: V,
+        exemplar: Option<&Exemplar<S, ES>>,
+    ) -> std::fmt::Result {
+        match self {
+            ValueEncoder::Text(e) => e.encode_value_and_exemplar(v, exemplar),
+            #[cfg(feature = "protobuf")]
+            ValueEncoder::Protobuf(e) => e.encode_value_and_exemplar(
+                v.as_f64(),
+                exemplar.map(|exemplar| {
+                    (
+                        protobuf::render_label_set(&exemplar.label_set),
+                        exemplar.value.as_f64(),
+                    )
+                }),
+            ),
+        }
+    }
+}
+
+/// Numeric sample value, encodable as OpenMetrics text (via [`text::Encode`])
+/// and, behind the `protobuf` feature, as the `double` the protobuf format
+/// represents all counter/gauge/histogram values with.
+pub trait EncodeValue: text::Encode {
+    fn as_f64(&self) -> f64;
+}
+
+impl EncodeValue for f64 {
+    fn as_f64(&self) -> f64 {
+        *self
+    }
+}
+
+impl EncodeValue for u64 {
+    fn as_f64(&self) -> f64 {
+        *self as f64
+    }
+}
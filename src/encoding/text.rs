@@ -25,7 +25,9 @@
 //! assert_eq!(expected, String::from_utf8(buffer).unwrap());
 //! ```
 
+use crate::encoding::{EncodeValue, MetricEncoder};
 use crate::metrics::counter::{self, Counter};
+use crate::metrics::exemplar::Exemplar;
 use crate::metrics::family::Family;
 use crate::metrics::gauge::{self, Gauge};
 use crate::metrics::histogram::Histogram;
@@ -33,57 +35,104 @@ use crate::metrics::{MetricType, TypedMetric};
 use crate::registry::{Registry, Unit};
 
 use generic_array::ArrayLength;
-use std::io::Write;
+use std::fmt::Write;
 use std::ops::Deref;
 
+/// Encodes `registry` into the OpenMetrics text format, writing the result to
+/// `writer`.
+///
+/// The encoding itself is built on [`std::fmt::Write`] (see [`Encode`]), as
+/// the OpenMetrics text format is, well, text; this function is a thin
+/// [`std::io::Write`] adapter kept at the public entry point for
+/// compatibility with callers writing to a socket or a byte buffer.
 pub fn encode<W, M>(writer: &mut W, registry: &Registry<M>) -> Result<(), std::io::Error>
 where
-    W: Write,
+    W: std::io::Write,
+    M: EncodeMetric,
+{
+    let mut buf = String::new();
+    encode_registry(&mut buf, registry)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+    writer.write_all(buf.as_bytes())
+}
+
+fn encode_registry<M>(writer: &mut dyn Write, registry: &Registry<M>) -> std::fmt::Result
+where
     M: EncodeMetric,
 {
     for (desc, metric) in registry.iter() {
-        writer.write_all(b"# HELP ")?;
-        writer.write_all(desc.name().as_bytes())?;
-        if let Some(unit) = desc.unit() {
-            writer.write_all(b"_")?;
-            unit.encode(writer)?;
-        }
-        writer.write_all(b" ")?;
-        writer.write_all(desc.help().as_bytes())?;
-        writer.write_all(b"\n")?;
-
-        writer.write_all(b"# TYPE ")?;
-        writer.write_all(desc.name().as_bytes())?;
-        if let Some(unit) = desc.unit() {
-            writer.write_all(b"_")?;
-            unit.encode(writer)?;
-        }
-        writer.write_all(b" ")?;
-        metric.metric_type().encode(writer)?;
-        writer.write_all(b"\n")?;
-
-        if let Some(unit) = desc.unit() {
-            writer.write_all(b"# UNIT ")?;
-            writer.write_all(desc.name().as_bytes())?;
-            writer.write_all(b"_")?;
-            unit.encode(writer)?;
-            writer.write_all(b" ")?;
-            unit.encode(writer)?;
-            writer.write_all(b"\n")?;
+        encode_descriptor_and_metric(writer, &desc, metric)?;
+    }
+
+    for collector in registry.collectors() {
+        for (desc, metric) in collector.collect() {
+            encode_descriptor_and_metric(writer, &desc, metric.as_ref())?;
         }
+    }
 
-        let encoder = Encoder {
-            writer,
-            name: &desc.name(),
-            unit: desc.unit(),
-            labels: None,
-        };
+    writer.write_str("# EOF\n")?;
 
-        metric.encode(encoder)?;
-    }
+    Ok(())
+}
 
-    writer.write_all(b"# EOF\n")?;
+fn encode_descriptor_and_metric(
+    writer: &mut dyn Write,
+    desc: &crate::registry::Descriptor,
+    metric: &dyn EncodeMetric,
+) -> std::fmt::Result {
+    writer.write_str("# HELP ")?;
+    writer.write_str(&desc.name())?;
+    if let Some(unit) = desc.unit() {
+        writer.write_str("_")?;
+        unit.encode(writer)?;
+    }
+    writer.write_str(" ")?;
+    encode_escaped(writer, desc.help(), false)?;
+    writer.write_str("\n")?;
+
+    writer.write_str("# TYPE ")?;
+    writer.write_str(&desc.name())?;
+    if let Some(unit) = desc.unit() {
+        writer.write_str("_")?;
+        unit.encode(writer)?;
+    }
+    writer.write_str(" ")?;
+    metric.metric_type().encode(writer)?;
+    writer.write_str("\n")?;
+
+    if let Some(unit) = desc.unit() {
+        writer.write_str("# UNIT ")?;
+        writer.write_str(&desc.name())?;
+        writer.write_str("_")?;
+        unit.encode(writer)?;
+        writer.write_str(" ")?;
+        unit.encode(writer)?;
+        writer.write_str("\n")?;
+    }
+
+    let encoder = Encoder {
+        writer,
+        name: &desc.name(),
+        unit: desc.unit(),
+        labels: None,
+    };
+
+    metric.encode(MetricEncoder::Text(encoder))
+}
 
+// Escapes `s` per the OpenMetrics text format: a literal backslash becomes
+// `\\`, a newline becomes `\n`, and, for label values specifically, a double
+// quote becomes `\"`. `# HELP` text is not quoted, so callers encoding it
+// pass `escape_double_quote: false`.
+fn encode_escaped(writer: &mut dyn Write, s: &str, escape_double_quote: bool) -> std::fmt::Result {
+    for c in s.chars() {
+        match c {
+            '\\' => writer.write_str("\\\\")?,
+            '"' if escape_double_quote => writer.write_str("\\\"")?,
+            '\n' => writer.write_str("\\n")?,
+            c => writer.write_char(c)?,
+        }
+    }
     Ok(())
 }
 
@@ -101,25 +150,25 @@ pub struct Encoder<'a, 'b> {
 }
 
 impl<'a, 'b> Encoder<'a, 'b> {
-    pub fn encode_suffix(&mut self, suffix: &'static str) -> Result<BucketEncoder, std::io::Error> {
+    pub fn encode_suffix(&mut self, suffix: &'static str) -> Result<BucketEncoder, std::fmt::Error> {
         self.write_name_and_unit()?;
 
-        self.writer.write_all(b"_")?;
-        self.writer.write_all(suffix.as_bytes()).map(|_| ())?;
+        self.writer.write_str("_")?;
+        self.writer.write_str(suffix)?;
 
         self.encode_labels()
     }
 
-    pub fn no_suffix(&mut self) -> Result<BucketEncoder, std::io::Error> {
+    pub fn no_suffix(&mut self) -> Result<BucketEncoder, std::fmt::Error> {
         self.write_name_and_unit()?;
 
         self.encode_labels()
     }
 
-    fn write_name_and_unit(&mut self) -> Result<(), std::io::Error> {
-        self.writer.write_all(self.name.as_bytes())?;
+    fn write_name_and_unit(&mut self) -> std::fmt::Result {
+        self.writer.write_str(self.name)?;
         if let Some(unit) = self.unit {
-            self.writer.write_all(b"_")?;
+            self.writer.write_str("_")?;
             unit.encode(self.writer)?;
         }
 
@@ -128,10 +177,25 @@ impl<'a, 'b> Encoder<'a, 'b> {
 
     // TODO: Consider caching the encoded labels for Histograms as they stay the
     // same but are currently encoded multiple times.
-    pub(self) fn encode_labels(&mut self) -> Result<BucketEncoder, std::io::Error> {
+    //
+    // Labels are rendered into a scratch buffer first rather than straight to
+    // `self.writer` so that a label set encoding to nothing (an empty
+    // `Vec<(String, String)>`, or [`NoLabelSet`]) never opens a dangling `{}`
+    // that some strict OpenMetrics parsers reject.
+    pub(self) fn encode_labels(&mut self) -> Result<BucketEncoder, std::fmt::Error> {
         if let Some(labels) = &self.labels {
-            self.writer.write_all(b"{")?;
-            labels.encode(self.writer)?;
+            let mut rendered = String::new();
+            labels.encode(&mut rendered)?;
+
+            if rendered.is_empty() {
+                return Ok(BucketEncoder {
+                    opened_curly_brackets: false,
+                    writer: self.writer,
+                });
+            }
+
+            self.writer.write_str("{")?;
+            self.writer.write_str(&rendered)?;
 
             Ok(BucketEncoder {
                 opened_curly_brackets: true,
@@ -155,6 +219,54 @@ impl<'a, 'b> Encoder<'a, 'b> {
             labels: Some(label_set),
         }
     }
+
+    /// Encodes a counter's `_total` sample, optionally under `label_set`.
+    ///
+    /// A convenience over `with_label_set(...).encode_suffix("total")?.no_bucket()?.encode_value(v)`
+    /// for custom [`EncodeMetric`] implementations that have no label set of
+    /// their own to apply.
+    pub fn encode_counter<V: Encode>(
+        &mut self,
+        label_set: Option<&dyn Encode>,
+        v: V,
+    ) -> std::fmt::Result {
+        match label_set {
+            Some(label_set) => self
+                .with_label_set(label_set)
+                .encode_suffix("total")?
+                .no_bucket()?
+                .encode_value(v),
+            None => self.encode_suffix("total")?.no_bucket()?.encode_value(v),
+        }
+    }
+
+    /// Encodes a gauge's sample, optionally under `label_set`. See
+    /// [`Self::encode_counter`].
+    pub fn encode_gauge<V: Encode>(
+        &mut self,
+        label_set: Option<&dyn Encode>,
+        v: V,
+    ) -> std::fmt::Result {
+        match label_set {
+            Some(label_set) => self
+                .with_label_set(label_set)
+                .no_suffix()?
+                .no_bucket()?
+                .encode_value(v),
+            None => self.no_suffix()?.no_bucket()?.encode_value(v),
+        }
+    }
+}
+
+/// A label set with no labels, e.g. for a custom [`EncodeMetric`]
+/// implementation that has none to encode. Encodes to nothing, so
+/// [`Encoder::encode_labels`] never emits an empty `{}`.
+pub struct NoLabelSet;
+
+impl Encode for NoLabelSet {
+    fn encode(&self, _writer: &mut dyn Write) -> std::fmt::Result {
+        Ok(())
+    }
 }
 
 #[must_use]
@@ -164,29 +276,29 @@ pub struct BucketEncoder<'a> {
 }
 
 impl<'a> BucketEncoder<'a> {
-    fn encode_bucket(&mut self, upper_bound: f64) -> Result<ValueEncoder, std::io::Error> {
+    pub(crate) fn encode_bucket(&mut self, upper_bound: f64) -> Result<ValueEncoder, std::fmt::Error> {
         if self.opened_curly_brackets {
-            self.writer.write_all(b", ")?;
+            self.writer.write_str(", ")?;
         } else {
-            self.writer.write_all(b"{")?;
+            self.writer.write_str("{")?;
         }
 
-        self.writer.write_all(b"le=\"")?;
+        self.writer.write_str("le=\"")?;
         if upper_bound == f64::MAX {
-            self.writer.write_all(b"+Inf")?;
+            self.writer.write_str("+Inf")?;
         } else {
             upper_bound.encode(self.writer)?;
         }
-        self.writer.write_all(b"\"}")?;
+        self.writer.write_str("\"}")?;
 
         Ok(ValueEncoder {
             writer: self.writer,
         })
     }
 
-    fn no_bucket(&mut self) -> Result<ValueEncoder, std::io::Error> {
+    pub(crate) fn no_bucket(&mut self) -> Result<ValueEncoder, std::fmt::Error> {
         if self.opened_curly_brackets {
-            self.writer.write_all(b"}")?;
+            self.writer.write_str("}")?;
         }
         Ok(ValueEncoder {
             writer: self.writer,
@@ -200,16 +312,37 @@ pub struct ValueEncoder<'a> {
 }
 
 impl<'a> ValueEncoder<'a> {
-    fn encode_value<V: Encode>(&mut self, v: V) -> Result<(), std::io::Error> {
-        self.writer.write_all(b" ")?;
+    pub(crate) fn encode_value<V: Encode>(&mut self, v: V) -> std::fmt::Result {
+        self.writer.write_str(" ")?;
         v.encode(self.writer)?;
-        self.writer.write_all(b"\n")?;
+        self.writer.write_str("\n")?;
+        Ok(())
+    }
+
+    // Writes ` v # {label="value",...} exemplar_value` before the trailing
+    // newline, per the OpenMetrics text format's exemplar suffix.
+    pub(crate) fn encode_value_and_exemplar<V: Encode, S: Encode, ES: Encode>(
+        &mut self,
+        v: V,
+        exemplar: Option<&Exemplar<S, ES>>,
+    ) -> std::fmt::Result {
+        self.writer.write_str(" ")?;
+        v.encode(self.writer)?;
+
+        if let Some(exemplar) = exemplar {
+            self.writer.write_str(" # {")?;
+            exemplar.label_set.encode(self.writer)?;
+            self.writer.write_str("} ")?;
+            exemplar.value.encode(self.writer)?;
+        }
+
+        self.writer.write_str("\n")?;
         Ok(())
     }
 }
 
 pub trait EncodeMetric {
-    fn encode(&self, encoder: Encoder) -> Result<(), std::io::Error>;
+    fn encode(&self, encoder: MetricEncoder) -> std::fmt::Result;
 
     // One can not use [`TypedMetric`] directly, as associated constants are not
     // object safe and thus can not be used with dynamic dispatching.
@@ -217,7 +350,7 @@ pub trait EncodeMetric {
 }
 
 impl EncodeMetric for Box<dyn EncodeMetric> {
-    fn encode(&self, encoder: Encoder) -> Result<(), std::io::Error> {
+    fn encode(&self, encoder: MetricEncoder) -> std::fmt::Result {
         self.deref().encode(encoder)
     }
 
@@ -231,7 +364,7 @@ pub trait SendEncodeMetric: EncodeMetric + Send {}
 impl<T: EncodeMetric + Send> SendEncodeMetric for T {}
 
 impl EncodeMetric for Box<dyn SendEncodeMetric> {
-    fn encode(&self, encoder: Encoder) -> Result<(), std::io::Error> {
+    fn encode(&self, encoder: MetricEncoder) -> std::fmt::Result {
         self.deref().encode(encoder)
     }
 
@@ -241,46 +374,58 @@ impl EncodeMetric for Box<dyn SendEncodeMetric> {
 }
 
 pub trait Encode {
-    fn encode(&self, writer: &mut dyn Write) -> Result<(), std::io::Error>;
+    /// Encodes `self` to `writer`.
+    ///
+    /// For a label set, this must render exactly
+    /// `name="value",name2="value2"` - comma-separated, each value wrapped in
+    /// `"` and escaped via [`encode_escaped`]'s `escape_double_quote: true`
+    /// form, as `Vec<(String, String)>`'s impl below does. The `protobuf`
+    /// feature's encoder (behind `crate::encoding::protobuf::render_label_set`)
+    /// has no other way to recover a label set's structured `(name, value)`
+    /// pairs, so it parses a label set's rendered text back apart assuming
+    /// exactly this syntax; a label set type that renders differently (no
+    /// quotes, a different separator, ...) will silently produce empty or
+    /// garbled protobuf labels.
+    fn encode(&self, writer: &mut dyn Write) -> std::fmt::Result;
 }
 
 impl Encode for f64 {
-    fn encode(&self, mut writer: &mut dyn Write) -> Result<(), std::io::Error> {
-        dtoa::write(&mut writer, *self)?;
-        Ok(())
+    fn encode(&self, writer: &mut dyn Write) -> std::fmt::Result {
+        let mut buf = [0u8; 24];
+        let len = dtoa::write(&mut buf[..], *self).map_err(|_| std::fmt::Error)?;
+        writer.write_str(std::str::from_utf8(&buf[..len]).map_err(|_| std::fmt::Error)?)
     }
 }
 
 impl Encode for u64 {
-    fn encode(&self, mut writer: &mut dyn Write) -> Result<(), std::io::Error> {
-        itoa::write(&mut writer, *self)?;
-        Ok(())
+    fn encode(&self, writer: &mut dyn Write) -> std::fmt::Result {
+        let mut buf = [0u8; 20];
+        let len = itoa::write(&mut buf[..], *self).map_err(|_| std::fmt::Error)?;
+        writer.write_str(std::str::from_utf8(&buf[..len]).map_err(|_| std::fmt::Error)?)
     }
 }
 
 impl Encode for &str {
-    fn encode(&self, writer: &mut dyn Write) -> Result<(), std::io::Error> {
-        // TODO: Can we do better?
-        writer.write_all(self.as_bytes())?;
-        Ok(())
+    fn encode(&self, writer: &mut dyn Write) -> std::fmt::Result {
+        encode_escaped(writer, self, true)
     }
 }
 
 impl Encode for Vec<(String, String)> {
-    fn encode(&self, writer: &mut dyn Write) -> Result<(), std::io::Error> {
+    fn encode(&self, writer: &mut dyn Write) -> std::fmt::Result {
         if self.is_empty() {
             return Ok(());
         }
 
         let mut iter = self.iter().peekable();
         while let Some((name, value)) = iter.next() {
-            writer.write_all(name.as_bytes())?;
-            writer.write_all(b"=\"")?;
-            writer.write_all(value.as_bytes())?;
-            writer.write_all(b"\"")?;
+            writer.write_str(name)?;
+            writer.write_str("=\"")?;
+            encode_escaped(writer, value, true)?;
+            writer.write_str("\"")?;
 
             if iter.peek().is_some() {
-                writer.write_all(b",")?;
+                writer.write_str(",")?;
             }
         }
 
@@ -289,7 +434,7 @@ impl Encode for Vec<(String, String)> {
 }
 
 impl Encode for MetricType {
-    fn encode(&self, writer: &mut dyn Write) -> Result<(), std::io::Error> {
+    fn encode(&self, writer: &mut dyn Write) -> std::fmt::Result {
         let t = match self {
             MetricType::Counter => "counter",
             MetricType::Gauge => "gauge",
@@ -297,13 +442,12 @@ impl Encode for MetricType {
             MetricType::Unknown => "unknown",
         };
 
-        writer.write_all(t.as_bytes())?;
-        Ok(())
+        writer.write_str(t)
     }
 }
 
 impl Encode for Unit {
-    fn encode(&self, writer: &mut dyn Write) -> Result<(), std::io::Error> {
+    fn encode(&self, writer: &mut dyn Write) -> std::fmt::Result {
         let u = match self {
             Unit::Amperes => "amperes",
             Unit::Bytes => "bytes",
@@ -317,23 +461,17 @@ impl Encode for Unit {
             Unit::Other(other) => other.as_str(),
         };
 
-        writer.write_all(u.as_bytes())?;
-        Ok(())
+        writer.write_str(u)
     }
 }
 
 impl<A> EncodeMetric for Counter<A>
 where
     A: counter::Atomic,
-    <A as counter::Atomic>::Number: Encode,
+    <A as counter::Atomic>::Number: EncodeValue,
 {
-    fn encode(&self, mut encoder: Encoder) -> Result<(), std::io::Error> {
-        encoder
-            .encode_suffix("total")?
-            .no_bucket()?
-            .encode_value(self.get())?;
-
-        Ok(())
+    fn encode(&self, mut encoder: MetricEncoder) -> std::fmt::Result {
+        encoder.encode_counter(None, self.get())
     }
 
     fn metric_type(&self) -> MetricType {
@@ -344,12 +482,10 @@ where
 impl<A> EncodeMetric for Gauge<A>
 where
     A: gauge::Atomic,
-    <A as gauge::Atomic>::Number: Encode,
+    <A as gauge::Atomic>::Number: EncodeValue,
 {
-    fn encode(&self, mut encoder: Encoder) -> Result<(), std::io::Error> {
-        encoder.no_suffix()?.no_bucket()?.encode_value(self.get())?;
-
-        Ok(())
+    fn encode(&self, mut encoder: MetricEncoder) -> std::fmt::Result {
+        encoder.encode_gauge(None, self.get())
     }
     fn metric_type(&self) -> MetricType {
         Self::TYPE
@@ -361,7 +497,7 @@ where
     S: Clone + std::hash::Hash + Eq + Encode,
     M: EncodeMetric + TypedMetric,
 {
-    fn encode(&self, mut encoder: Encoder) -> Result<(), std::io::Error> {
+    fn encode(&self, mut encoder: MetricEncoder) -> std::fmt::Result {
         let guard = self.read();
         for (label_set, m) in guard.iter() {
             let encoder = encoder.with_label_set(label_set);
@@ -376,7 +512,7 @@ where
 }
 
 impl<NumBuckets: ArrayLength<(f64, u64)>> EncodeMetric for Histogram<NumBuckets> {
-    fn encode(&self, mut encoder: Encoder) -> Result<(), std::io::Error> {
+    fn encode(&self, mut encoder: MetricEncoder) -> std::fmt::Result {
         let (sum, count, buckets) = self.get();
         encoder
             .encode_suffix("sum")?
@@ -408,9 +544,9 @@ mod tests {
     use crate::metrics::counter::Counter;
     use crate::metrics::gauge::Gauge;
     use crate::metrics::histogram::exponential_series;
+    use generic_array::typenum::U10;
     use pyo3::{prelude::*, types::PyModule};
     use std::sync::atomic::AtomicU64;
-    use generic_array::typenum::U10;
 
     #[test]
     fn encode_counter() {
@@ -457,6 +593,29 @@ mod tests {
         parse_with_python_client(String::from_utf8(encoded).unwrap());
     }
 
+    #[test]
+    fn encode_counter_family_with_escaped_label_value() {
+        let mut registry = Registry::default();
+        let family = Family::<Vec<(String, String)>, Counter<AtomicU64>>::default();
+        registry.register("my_counter_family", "My counter family", family.clone());
+
+        family
+            .get_or_create(&vec![(
+                "path".to_string(),
+                "C:\\foo\\\"bar\"\nbaz".to_string(),
+            )])
+            .inc();
+
+        let mut encoded = Vec::new();
+
+        encode(&mut encoded, &registry).unwrap();
+
+        let encoded = String::from_utf8(encoded).unwrap();
+        assert!(encoded.contains("path=\"C:\\\\foo\\\\\\\"bar\\\"\\nbaz\""));
+
+        parse_with_python_client(encoded);
+    }
+
     #[test]
     fn encode_counter_family() {
         let mut registry = Registry::default();
@@ -474,6 +633,55 @@ mod tests {
         parse_with_python_client(String::from_utf8(encoded).unwrap());
     }
 
+    #[test]
+    fn encode_counter_family_with_empty_label_set() {
+        let mut registry = Registry::default();
+        let family = Family::<Vec<(String, String)>, Counter<AtomicU64>>::default();
+        registry.register("my_counter_family", "My counter family", family.clone());
+
+        family.get_or_create(&vec![]).inc();
+
+        let mut encoded = Vec::new();
+
+        encode(&mut encoded, &registry).unwrap();
+
+        let encoded = String::from_utf8(encoded).unwrap();
+        assert!(!encoded.contains('{'));
+
+        parse_with_python_client(encoded);
+    }
+
+    #[test]
+    fn encode_counter_with_no_label_set() {
+        // A custom `EncodeMetric` with no label set of its own opts out via
+        // `NoLabelSet` rather than restructuring around `Option::None`.
+        struct CustomCounter(Counter<AtomicU64>);
+
+        impl EncodeMetric for CustomCounter {
+            fn encode(&self, mut encoder: MetricEncoder) -> std::fmt::Result {
+                encoder.encode_counter(Some(&NoLabelSet), self.0.get())
+            }
+
+            fn metric_type(&self) -> MetricType {
+                MetricType::Counter
+            }
+        }
+
+        let mut registry = Registry::default();
+        let counter = Counter::<AtomicU64>::new();
+        counter.inc();
+        registry.register("my_counter", "My counter", CustomCounter(counter));
+
+        let mut encoded = Vec::new();
+        encode(&mut encoded, &registry).unwrap();
+        let encoded = String::from_utf8(encoded).unwrap();
+
+        assert!(encoded.contains("my_counter_total 1\n"));
+        assert!(!encoded.contains('{'));
+
+        parse_with_python_client(encoded);
+    }
+
     #[test]
     fn encode_histogram() {
         let mut registry = Registry::default();
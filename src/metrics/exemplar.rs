@@ -0,0 +1,329 @@
+//! Exemplars, as defined by the OpenMetrics specification:
+//! <https://github.com/OpenObservability/OpenMetrics/blob/main/specification/OpenMetrics.md#exemplars>
+//!
+//! An exemplar correlates a sample with, typically, a trace ID, letting a
+//! consumer jump from a counter or histogram bucket straight to the request
+//! that produced it. Only [`CounterWithExemplar`] and
+//! [`HistogramWithExemplars`] carry exemplars; plain [`Counter`] and
+//! [`Histogram`] do not pay for the bookkeeping.
+
+use crate::encoding::text::{Encode, EncodeMetric};
+use crate::encoding::{EncodeValue, MetricEncoder};
+use crate::metrics::counter::{self, Counter};
+use crate::metrics::histogram::Histogram;
+use crate::metrics::{MetricType, TypedMetric};
+
+use generic_array::typenum::Unsigned;
+use generic_array::{ArrayLength, GenericArray};
+use std::sync::{Arc, Mutex};
+
+/// Combined UTF-8 character limit for an exemplar's label set, as mandated
+/// by the OpenMetrics specification.
+const MAX_LABEL_SET_LENGTH: usize = 128;
+
+/// A sample annotated with an exemplar, e.g. a trace ID.
+#[derive(Debug, Clone)]
+pub struct Exemplar<S, V> {
+    pub label_set: S,
+    pub value: V,
+}
+
+/// The exemplar's label set rendered to more than 128 UTF-8 characters,
+/// exceeding the OpenMetrics limit.
+#[derive(Debug)]
+pub struct LabelSetTooLong {
+    pub length: usize,
+}
+
+impl std::fmt::Display for LabelSetTooLong {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "exemplar label set is {} UTF-8 characters long, the OpenMetrics limit is {}",
+            self.length, MAX_LABEL_SET_LENGTH
+        )
+    }
+}
+
+impl std::error::Error for LabelSetTooLong {}
+
+impl<S: Encode, V> Exemplar<S, V> {
+    pub fn new(label_set: S, value: V) -> Result<Self, LabelSetTooLong> {
+        let mut rendered = String::new();
+        label_set.encode(&mut rendered).ok();
+        let length = rendered.chars().count();
+
+        if length > MAX_LABEL_SET_LENGTH {
+            return Err(LabelSetTooLong { length });
+        }
+
+        Ok(Self { label_set, value })
+    }
+}
+
+/// Like [`Counter`], but each observation may additionally carry an
+/// [`Exemplar`]. The exemplar of the most recent observation that provided
+/// one is the one encoded.
+pub struct CounterWithExemplar<S, A: counter::Atomic> {
+    counter: Counter<A>,
+    exemplar: Arc<Mutex<Option<Exemplar<S, A::Number>>>>,
+}
+
+impl<S, A: counter::Atomic> Clone for CounterWithExemplar<S, A> {
+    fn clone(&self) -> Self {
+        Self {
+            counter: self.counter.clone(),
+            exemplar: self.exemplar.clone(),
+        }
+    }
+}
+
+impl<S, A: counter::Atomic + Default> Default for CounterWithExemplar<S, A> {
+    fn default() -> Self {
+        Self {
+            counter: Counter::default(),
+            exemplar: Arc::new(Mutex::new(None)),
+        }
+    }
+}
+
+impl<S, A: counter::Atomic> CounterWithExemplar<S, A> {
+    pub fn new() -> Self
+    where
+        A: Default,
+    {
+        Self::default()
+    }
+
+    /// Increments the counter by `v`, optionally attaching an exemplar.
+    ///
+    /// A label set whose rendered length exceeds the OpenMetrics exemplar
+    /// limit is silently dropped rather than attached; the counter is still
+    /// incremented.
+    pub fn inc_by_with_exemplar(&self, v: A::Number, label_set: Option<S>) -> A::Number
+    where
+        S: Encode,
+        A::Number: Clone,
+    {
+        if let Some(label_set) = label_set {
+            if let Ok(exemplar) = Exemplar::new(label_set, v.clone()) {
+                *self.exemplar.lock().unwrap() = Some(exemplar);
+            }
+        }
+
+        self.counter.inc_by(v)
+    }
+
+    pub fn get(&self) -> A::Number {
+        self.counter.get()
+    }
+
+    pub fn exemplar(&self) -> Option<Exemplar<S, A::Number>>
+    where
+        S: Clone,
+        A::Number: Clone,
+    {
+        self.exemplar.lock().unwrap().clone()
+    }
+}
+
+impl<S, A: counter::Atomic> TypedMetric for CounterWithExemplar<S, A> {
+    const TYPE: MetricType = MetricType::Counter;
+}
+
+impl<S, A> EncodeMetric for CounterWithExemplar<S, A>
+where
+    S: Encode + Clone,
+    A: counter::Atomic,
+    A::Number: EncodeValue + Clone,
+{
+    fn encode(&self, mut encoder: MetricEncoder) -> std::fmt::Result {
+        let exemplar = self.exemplar.lock().unwrap().clone();
+        encoder
+            .encode_suffix("total")?
+            .no_bucket()?
+            .encode_value_and_exemplar(self.get(), exemplar.as_ref())?;
+
+        Ok(())
+    }
+
+    fn metric_type(&self) -> MetricType {
+        Self::TYPE
+    }
+}
+
+/// Like [`Histogram`], but each bucket may additionally carry an
+/// [`Exemplar`] for the most recent observation that fell into it.
+pub struct HistogramWithExemplars<S, NumBuckets: ArrayLength<(f64, u64)>> {
+    histogram: Histogram<NumBuckets>,
+    exemplars: Arc<Mutex<Vec<Option<Exemplar<S, f64>>>>>,
+}
+
+impl<S, NumBuckets: ArrayLength<(f64, u64)>> Clone for HistogramWithExemplars<S, NumBuckets> {
+    fn clone(&self) -> Self {
+        Self {
+            histogram: self.histogram.clone(),
+            exemplars: self.exemplars.clone(),
+        }
+    }
+}
+
+impl<S, NumBuckets: ArrayLength<(f64, u64)>> HistogramWithExemplars<S, NumBuckets> {
+    pub fn new(buckets: impl Iterator<Item = f64>) -> Self {
+        // One exemplar slot per histogram bucket; `buckets` may be unbounded.
+        let num_buckets = NumBuckets::to_usize();
+
+        Self {
+            histogram: Histogram::new(buckets),
+            exemplars: Arc::new(Mutex::new((0..num_buckets).map(|_| None).collect())),
+        }
+    }
+
+    /// Observes `v`, optionally attaching an exemplar to the bucket `v`
+    /// falls into.
+    ///
+    /// A label set whose rendered length exceeds the OpenMetrics exemplar
+    /// limit is silently dropped rather than attached; the observation is
+    /// still recorded.
+    pub fn observe_with_exemplar(&self, v: f64, label_set: Option<S>)
+    where
+        S: Encode,
+    {
+        self.histogram.observe(v);
+
+        let label_set = match label_set {
+            Some(label_set) => label_set,
+            None => return,
+        };
+
+        let exemplar = match Exemplar::new(label_set, v) {
+            Ok(exemplar) => exemplar,
+            Err(_) => return,
+        };
+
+        let (_, _, buckets) = self.histogram.get();
+        if let Some(index) = buckets.iter().position(|(upper_bound, _)| v <= *upper_bound) {
+            let mut exemplars = self.exemplars.lock().unwrap();
+            if let Some(slot) = exemplars.get_mut(index) {
+                *slot = Some(exemplar);
+            }
+        }
+    }
+
+    pub fn get(&self) -> (f64, u64, GenericArray<(f64, u64), NumBuckets>) {
+        self.histogram.get()
+    }
+}
+
+impl<S, NumBuckets: ArrayLength<(f64, u64)>> TypedMetric for HistogramWithExemplars<S, NumBuckets> {
+    const TYPE: MetricType = MetricType::Histogram;
+}
+
+impl<S, NumBuckets> EncodeMetric for HistogramWithExemplars<S, NumBuckets>
+where
+    S: Encode + Clone,
+    NumBuckets: ArrayLength<(f64, u64)>,
+{
+    fn encode(&self, mut encoder: MetricEncoder) -> std::fmt::Result {
+        let (sum, count, buckets) = self.histogram.get();
+        encoder
+            .encode_suffix("sum")?
+            .no_bucket()?
+            .encode_value(sum)?;
+        encoder
+            .encode_suffix("count")?
+            .no_bucket()?
+            .encode_value(count)?;
+
+        let exemplars = self.exemplars.lock().unwrap();
+        for (index, (upper_bound, count)) in buckets.iter().enumerate() {
+            let exemplar = exemplars.get(index).and_then(Option::as_ref);
+            encoder
+                .encode_suffix("bucket")?
+                .encode_bucket(*upper_bound)?
+                .encode_value_and_exemplar(*count, exemplar)?;
+        }
+
+        Ok(())
+    }
+
+    fn metric_type(&self) -> MetricType {
+        Self::TYPE
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoding::text::encode;
+    use crate::metrics::histogram::exponential_series;
+    use crate::registry::Registry;
+    use generic_array::typenum::U10;
+    use pyo3::{prelude::*, types::PyModule};
+    use std::sync::atomic::AtomicU64;
+
+    #[test]
+    fn encode_counter_with_exemplar() {
+        let mut registry = Registry::default();
+        let counter = CounterWithExemplar::<Vec<(String, String)>, AtomicU64>::new();
+        registry.register("my_counter", "My counter", counter.clone());
+
+        counter.inc_by_with_exemplar(
+            1,
+            Some(vec![("trace_id".to_string(), "abc123".to_string())]),
+        );
+
+        let mut encoded = Vec::new();
+        encode(&mut encoded, &registry).unwrap();
+        let encoded = String::from_utf8(encoded).unwrap();
+
+        assert!(encoded.contains("my_counter_total 1 # {trace_id=\"abc123\"} 1\n"));
+
+        parse_with_python_client(encoded);
+    }
+
+    #[test]
+    fn encode_histogram_with_exemplars() {
+        let mut registry = Registry::default();
+        let histogram = HistogramWithExemplars::<Vec<(String, String)>, U10>::new(
+            exponential_series(1.0, 2.0),
+        );
+        registry.register("my_histogram", "My histogram", histogram.clone());
+
+        histogram.observe_with_exemplar(
+            1.0,
+            Some(vec![("trace_id".to_string(), "abc123".to_string())]),
+        );
+
+        let mut encoded = Vec::new();
+        encode(&mut encoded, &registry).unwrap();
+        let encoded = String::from_utf8(encoded).unwrap();
+
+        assert!(encoded.contains("# {trace_id=\"abc123\"} 1"));
+
+        parse_with_python_client(encoded);
+    }
+
+    fn parse_with_python_client(input: String) {
+        Python::with_gil(|py| {
+            let parser = PyModule::from_code(
+                py,
+                r#"
+from prometheus_client.openmetrics.parser import text_string_to_metric_families
+
+def parse(input):
+    families = text_string_to_metric_families(input)
+    list(families)
+"#,
+                "parser.py",
+                "parser",
+            )
+            .map_err(|e| e.to_string())
+            .unwrap();
+            parser
+                .call1("parse", (input,))
+                .map_err(|e| e.to_string())
+                .unwrap();
+        })
+    }
+}
@@ -0,0 +1,127 @@
+//! A registry of metrics, and their descriptors, to be encoded.
+
+use crate::collector::Collector;
+
+/// A registry of metrics.
+///
+/// Metrics are registered once, by name and help text, and thereafter
+/// encoded (see [`crate::encoding`]) alongside the samples of any
+/// [`Collector`]s registered via [`Self::register_collector`].
+pub struct Registry<M> {
+    metrics: Vec<(Descriptor, M)>,
+    collectors: Vec<Box<dyn Collector>>,
+}
+
+// Not `#[derive(Default)]`: that would add an `M: Default` bound even though
+// neither field actually needs one, breaking e.g. `Registry::<Box<dyn
+// EncodeMetric>>::default()`.
+impl<M> Default for Registry<M> {
+    fn default() -> Self {
+        Self {
+            metrics: Vec::new(),
+            collectors: Vec::new(),
+        }
+    }
+}
+
+impl<M> Registry<M> {
+    /// Registers `metric` under `name` with `help` text, to which a trailing
+    /// `.` is appended.
+    pub fn register<N: Into<String>, H: Into<String>>(&mut self, name: N, help: H, metric: M) {
+        self.metrics.push((
+            Descriptor {
+                name: name.into(),
+                help: help.into() + ".",
+                unit: None,
+            },
+            metric,
+        ));
+    }
+
+    /// Like [`Self::register`], but additionally attaches `unit` to the
+    /// metric's name and `# UNIT` line.
+    pub fn register_with_unit<N: Into<String>, H: Into<String>>(
+        &mut self,
+        name: N,
+        help: H,
+        unit: Unit,
+        metric: M,
+    ) {
+        self.metrics.push((
+            Descriptor {
+                name: name.into(),
+                help: help.into() + ".",
+                unit: Some(unit),
+            },
+            metric,
+        ));
+    }
+
+    /// Registers `collector`, whose metrics are computed lazily and encoded
+    /// alongside the registry's normally registered metrics on every
+    /// [`crate::encoding::text::encode`]/[`crate::encoding::protobuf::encode`]
+    /// call.
+    pub fn register_collector(&mut self, collector: Box<dyn Collector>) {
+        self.collectors.push(collector);
+    }
+
+    /// Iterates the descriptors and metrics registered via
+    /// [`Self::register`]/[`Self::register_with_unit`].
+    pub fn iter(&self) -> impl Iterator<Item = (Descriptor, &M)> {
+        self.metrics.iter().map(|(d, m)| (d.clone(), m))
+    }
+
+    /// Iterates the collectors registered via [`Self::register_collector`].
+    pub fn collectors(&self) -> impl Iterator<Item = &Box<dyn Collector>> {
+        self.collectors.iter()
+    }
+}
+
+/// A metric's name, help text, and, optionally, its unit.
+#[derive(Clone)]
+pub struct Descriptor {
+    name: String,
+    help: String,
+    unit: Option<Unit>,
+}
+
+impl Descriptor {
+    /// Constructs a descriptor directly, e.g. from a [`Collector`]
+    /// implementation, which has no [`Registry`] slot of its own to read a
+    /// descriptor back out of.
+    pub fn new<N: Into<String>, H: Into<String>>(name: N, help: H, unit: Option<Unit>) -> Self {
+        Self {
+            name: name.into(),
+            help: help.into(),
+            unit,
+        }
+    }
+
+    pub fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    pub fn help(&self) -> &str {
+        &self.help
+    }
+
+    pub fn unit(&self) -> &Option<Unit> {
+        &self.unit
+    }
+}
+
+/// A metric's unit, per the
+/// [OpenMetrics `UNIT` metadata](https://github.com/OpenObservability/OpenMetrics/blob/main/specification/OpenMetrics.md#unit).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Unit {
+    Amperes,
+    Bytes,
+    Celsius,
+    Grams,
+    Joules,
+    Meters,
+    Ratios,
+    Seconds,
+    Volts,
+    Other(String),
+}